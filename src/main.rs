@@ -1,7 +1,10 @@
 extern crate nix;
 
+use nix::sys::signal;
 use nix::sys::termios;
-use std::io::{ErrorKind, Read};
+use nix::unistd::isatty;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{fs::File, os::unix::io::AsRawFd};
 
 type GenericError = Box<dyn std::error::Error>;
@@ -36,6 +39,63 @@ fn get_window_size(fd: i32) -> Result<UVec2, GenericError> {
     })
 }
 
+// Fallback for when TIOCGWINSZ isn't available (e.g. some pipes/pty
+// setups): push the cursor as far right/down as it'll go, which stops it at
+// the real edge of the terminal, then ask for a Device Status Report and
+// parse the resulting "\x1b[<rows>;<cols>R" cursor position reply.
+fn get_window_size_via_cursor(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+) -> Result<UVec2, GenericError> {
+    output.write_all(b"\x1b[999C\x1b[999B\x1b[6n")?;
+    output.flush()?;
+
+    let mut reply = Vec::new();
+    loop {
+        match read_key(input)? {
+            Some(b'R') => break,
+            Some(c) => reply.push(c),
+            None => return Err("timed out waiting for cursor position report".into()),
+        }
+    }
+
+    let start = reply
+        .iter()
+        .position(|&c| c == b'[')
+        .ok_or("malformed cursor position report")?;
+    let body = std::str::from_utf8(&reply[start + 1..])?;
+    let (rows, cols) = body
+        .split_once(';')
+        .ok_or("malformed cursor position report")?;
+
+    Ok(UVec2 {
+        x: cols.parse()?,
+        y: rows.parse()?,
+    })
+}
+
+// Set by handle_sigwinch and polled from the update loop, since signal
+// handlers can't safely touch the Editor directly.
+static WINDOW_RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: nix::libc::c_int) {
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+}
+
+fn install_sigwinch_handler() -> Result<(), GenericError> {
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(handle_sigwinch),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+
+    unsafe {
+        signal::sigaction(signal::Signal::SIGWINCH, &action)?;
+    }
+
+    Ok(())
+}
+
 fn raw_mode_params(termios: &mut termios::Termios) {
     // set character size to 8 bits per byte (probably default)
     termios.control_flags |= termios::ControlFlags::CS8;
@@ -92,6 +152,241 @@ fn read_key(input: &mut dyn Read) -> Result<Option<u8>, std::io::Error> {
     return Ok(Some(buf[0]));
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Delete,
+    Ctrl(u8),
+    Esc,
+}
+
+// Reads a single logical keypress, decoding VT100 escape sequences for
+// cursor/navigation keys. A lone ESC with no following bytes (i.e. a read
+// timeout) decodes to Key::Esc rather than blocking for more input.
+fn read_key_decoded(input: &mut dyn Read) -> Result<Option<Key>, std::io::Error> {
+    let c = match read_key(input)? {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    if c == b'\x1b' {
+        let next = match read_key(input)? {
+            Some(c) => c,
+            None => return Ok(Some(Key::Esc)),
+        };
+
+        if next == b'[' {
+            let seq = match read_key(input)? {
+                Some(c) => c,
+                None => return Ok(Some(Key::Esc)),
+            };
+
+            return Ok(Some(match seq {
+                b'A' => Key::ArrowUp,
+                b'B' => Key::ArrowDown,
+                b'C' => Key::ArrowRight,
+                b'D' => Key::ArrowLeft,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                b'0'..=b'9' => {
+                    // numeric "tilde" sequence: ESC [ <digits> ~
+                    let mut digits = vec![seq];
+                    loop {
+                        match read_key(input)? {
+                            Some(b'~') => break,
+                            Some(d) if d.is_ascii_digit() => digits.push(d),
+                            _ => return Ok(Some(Key::Esc)),
+                        }
+                    }
+
+                    match digits.as_slice() {
+                        [b'1'] | [b'7'] => Key::Home,
+                        [b'4'] | [b'8'] => Key::End,
+                        [b'3'] => Key::Delete,
+                        [b'5'] => Key::PageUp,
+                        [b'6'] => Key::PageDown,
+                        _ => Key::Esc,
+                    }
+                }
+                _ => Key::Esc,
+            }));
+        }
+
+        // ESC O H / ESC O F: alternate Home/End encoding used in application
+        // keypad mode.
+        if next == b'O' {
+            return Ok(Some(match read_key(input)? {
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                _ => Key::Esc,
+            }));
+        }
+
+        return Ok(Some(Key::Esc));
+    }
+
+    if c < 0x20 {
+        return Ok(Some(Key::Ctrl(c)));
+    }
+
+    let continuation_bytes = if c & 0x80 == 0 {
+        0
+    } else if c & 0xe0 == 0xc0 {
+        1
+    } else if c & 0xf0 == 0xe0 {
+        2
+    } else if c & 0xf8 == 0xf0 {
+        3
+    } else {
+        // not a valid UTF-8 lead byte; surface it as-is
+        return Ok(Some(Key::Char(c as char)));
+    };
+
+    let mut bytes = vec![c];
+    for _ in 0..continuation_bytes {
+        // the continuation bytes of a real UTF-8 sequence follow the lead
+        // byte immediately, so a timeout here means more data is still on
+        // its way rather than that the user stopped typing; retry instead
+        // of dropping the bytes we've already read. After three timeouts in
+        // a row the partial sequence is discarded and decodes to Key::Esc.
+        match read_key_retrying(input, 3)? {
+            Some(cont) => bytes.push(cont),
+            None => return Ok(Some(Key::Esc)),
+        }
+    }
+
+    match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+        Some(ch) => Ok(Some(Key::Char(ch))),
+        None => Ok(Some(Key::Esc)),
+    }
+}
+
+fn read_key_retrying(
+    input: &mut dyn Read,
+    max_attempts: u32,
+) -> Result<Option<u8>, std::io::Error> {
+    for _ in 0..max_attempts {
+        if let Some(c) = read_key(input)? {
+            return Ok(Some(c));
+        }
+    }
+
+    Ok(None)
+}
+
+// Returns the number of terminal columns a character occupies: 0 for
+// combining marks (which render on top of the preceding column), 2 for wide
+// codepoints such as CJK ideographs, and 1 otherwise.
+fn display_width(c: char) -> usize {
+    let cp = c as u32;
+
+    if matches!(cp,
+        0x0300..=0x036f | 0x1ab0..=0x1aff | 0x1dc0..=0x1dff | 0x20d0..=0x20ff
+    ) {
+        return 0;
+    }
+
+    if matches!(cp,
+        0x1100..=0x115f
+            | 0x2e80..=0xa4cf
+            | 0xac00..=0xd7a3
+            | 0xf900..=0xfaff
+            | 0xff00..=0xff60
+            | 0xffe0..=0xffe6
+            | 0x20000..=0x3fffd
+    ) {
+        return 2;
+    }
+
+    1
+}
+
+// Returns the portion of `row` that's visible in a window `width` display
+// columns wide, starting at display column `coloff`.
+fn clip_row(row: &str, coloff: usize, width: usize) -> String {
+    let mut clipped = String::new();
+    let mut col = 0;
+
+    for c in row.chars() {
+        if col + display_width(c) > coloff + width {
+            break;
+        }
+
+        if col >= coloff {
+            clipped.push(c);
+        }
+
+        col += display_width(c);
+    }
+
+    clipped
+}
+
+fn row_width(row: &str) -> usize {
+    row.chars().map(display_width).sum()
+}
+
+// Applies raw mode for the lifetime of the guard and restores the
+// terminal's previous settings when dropped, so the user's shell is left
+// intact on normal exit, early returns, and unwinding panics alike.
+struct RawGuard {
+    tty_fd: i32,
+    prev_term_settings: termios::Termios,
+}
+
+// TERM values known to not support the VT100 escape sequences this editor
+// relies on for raw-mode rendering.
+const UNSUPPORTED_TERMS: &[&str] = &["dumb", "cons25", "emacs"];
+
+fn check_terminal_supported(tty_fd: i32) -> Result<(), GenericError> {
+    if !isatty(tty_fd)? {
+        return Err(format!("textedit requires a tty; fd {} is not a terminal", tty_fd).into());
+    }
+
+    if let Ok(term) = std::env::var("TERM") {
+        if UNSUPPORTED_TERMS.contains(&term.as_str()) {
+            return Err(format!("textedit doesn't support TERM={}", term).into());
+        }
+    }
+
+    Ok(())
+}
+
+impl RawGuard {
+    fn new(tty_fd: i32) -> Result<RawGuard, GenericError> {
+        check_terminal_supported(tty_fd)?;
+
+        let prev_term_settings = termios::tcgetattr(tty_fd)?;
+        let mut term_settings = prev_term_settings.clone();
+        raw_mode_params(&mut term_settings);
+        termios::tcsetattr(tty_fd, termios::SetArg::TCSANOW, &term_settings)?;
+
+        Ok(RawGuard {
+            tty_fd,
+            prev_term_settings,
+        })
+    }
+}
+
+impl Drop for RawGuard {
+    fn drop(&mut self) {
+        // best-effort: nothing we can do with the error while dropping
+        let _ = termios::tcsetattr(
+            self.tty_fd,
+            termios::SetArg::TCSANOW,
+            &self.prev_term_settings,
+        );
+    }
+}
+
 const SHOW_CURSOR: &'static [u8] = b"\x1b[?25h";
 const HIDE_CURSOR: &'static [u8] = b"\x1b[?25l";
 const CLEAR_SCREEN: &'static [u8] = b"\x1b[2J";
@@ -103,12 +398,16 @@ struct Editor<'a> {
     framebuf: Vec<u8>,
     quit: bool,
 
+    rows: Vec<String>,
+    rowoff: usize,
+    coloff: usize,
+
     tty_fd: i32,
     istream: &'a mut dyn std::io::Read,
     ostream: &'a mut dyn std::io::Write,
 
-    term_settings: termios::Termios,
-    prev_term_settings: termios::Termios,
+    // held only to restore the terminal on drop; never read otherwise
+    _raw_guard: RawGuard,
     size: UVec2,
 }
 
@@ -118,34 +417,33 @@ impl Editor<'_> {
         ostream: &'a mut dyn std::io::Write,
         tty_fd: i32,
     ) -> Result<Editor<'a>, GenericError> {
-        let prev_term_settings = termios::tcgetattr(tty_fd)?;
-        let mut term_settings = prev_term_settings.clone();
-        raw_mode_params(&mut term_settings);
+        let _raw_guard = RawGuard::new(tty_fd)?;
+
+        let size = match get_window_size(tty_fd) {
+            Ok(size) => size,
+            Err(_) => get_window_size_via_cursor(istream, ostream)?,
+        };
+
+        install_sigwinch_handler()?;
 
         Ok(Editor {
             curpos: UVec2 { x: 0, y: 0 },
             framebuf: Vec::new(),
             quit: false,
+            rows: Vec::new(),
+            rowoff: 0,
+            coloff: 0,
             istream,
             ostream,
             tty_fd,
-            term_settings,
-            prev_term_settings,
-            size: get_window_size(tty_fd)?,
+            _raw_guard,
+            size,
         })
     }
 
-    fn apply_term_settings(&self) -> Result<(), GenericError> {
-        termios::tcsetattr(self.tty_fd, termios::SetArg::TCSANOW, &self.term_settings)?;
-        Ok(())
-    }
-
-    fn apply_prev_term_settings(&self) -> Result<(), GenericError> {
-        termios::tcsetattr(
-            self.tty_fd,
-            termios::SetArg::TCSANOW,
-            &self.prev_term_settings,
-        )?;
+    fn open(&mut self, path: &str) -> Result<(), GenericError> {
+        let contents = std::fs::read_to_string(path)?;
+        self.rows = contents.lines().map(str::to_string).collect();
         Ok(())
     }
 
@@ -161,79 +459,148 @@ impl Editor<'_> {
     }
 
     fn handle_input(&mut self) -> Result<bool, GenericError> {
-        let res = read_key(self.istream)?;
-        if res.is_none() {
-            return Ok(false);
+        let key = match read_key_decoded(self.istream)? {
+            Some(key) => key,
+            None => return Ok(false),
+        };
+
+        match key {
+            // handle quit
+            Key::Ctrl(c) if c == ctrl_chord(b'q') => {
+                self.quit = true;
+                return Ok(true);
+            }
+            Key::ArrowUp => {
+                if self.curpos.y > 0 {
+                    self.curpos.y -= 1;
+                }
+            }
+            Key::ArrowDown => {
+                if self.curpos.y < self.max_curpos_y() {
+                    self.curpos.y += 1;
+                }
+            }
+            Key::ArrowRight => {
+                if self.curpos.x < self.current_row_width() {
+                    self.curpos.x += 1;
+                }
+            }
+            Key::ArrowLeft => {
+                if self.curpos.x > 0 {
+                    self.curpos.x -= 1;
+                }
+            }
+            Key::PageUp => {
+                // move the cursor by a full screen height
+                self.curpos.y = self.curpos.y.saturating_sub(self.size.y);
+            }
+            Key::PageDown => {
+                self.curpos.y = (self.curpos.y + self.size.y).min(self.max_curpos_y());
+            }
+            // do nothing by default
+            _ => (),
         }
 
-        let c = res.unwrap();
+        // a vertical move can land the cursor past the end of a shorter row
+        self.curpos.x = self.curpos.x.min(self.current_row_width());
+        self.clamp_scroll();
 
-        // handle quit
-        if c == ctrl_chord(b'q') {
-            self.quit = true;
-            return Ok(true);
+        Ok(true)
+    }
+
+    // The highest row the cursor may occupy: the last loaded row, or the
+    // bottom of the screen when there's no document loaded.
+    fn max_curpos_y(&self) -> usize {
+        if self.rows.is_empty() {
+            self.size.y - 1
+        } else {
+            self.rows.len() - 1
         }
+    }
 
-        // handle escape sequences
-        if c == b'\x1b' {
-            if let Some(true) = read_key(self.istream)?.map(|c| c == b'[') {
-                match read_key(self.istream)? {
-                    // up arrow
-                    Some(b'A') => {
-                        if self.curpos.y > 0 {
-                            self.curpos.y -= 1;
-                        }
-                    }
-                    // down arrow
-                    Some(b'B') => {
-                        if self.curpos.y < self.size.y - 1 {
-                            self.curpos.y += 1;
-                        }
-                    }
-                    // right arrow
-                    Some(b'C') => {
-                        if self.curpos.x < self.size.x - 1 {
-                            self.curpos.x += 1;
-                        }
-                    }
-                    // left arrow
-                    Some(b'D') => {
-                        if self.curpos.x > 0 {
-                            self.curpos.x -= 1;
-                        }
-                    }
-                    // do nothing by default
-                    _ => (),
-                }
-            }
+    fn current_row_width(&self) -> usize {
+        if self.rows.is_empty() {
+            return self.size.x.saturating_sub(1);
         }
 
-        Ok(true)
+        self.rows
+            .get(self.curpos.y)
+            .map(|row| row_width(row))
+            .unwrap_or(0)
+    }
+
+    // Keeps rowoff/coloff such that curpos stays within the visible window.
+    fn clamp_scroll(&mut self) {
+        if self.curpos.y < self.rowoff {
+            self.rowoff = self.curpos.y;
+        }
+        if self.curpos.y >= self.rowoff + self.size.y {
+            self.rowoff = self.curpos.y - self.size.y + 1;
+        }
+
+        if self.curpos.x < self.coloff {
+            self.coloff = self.curpos.x;
+        }
+        if self.curpos.x >= self.coloff + self.size.x {
+            self.coloff = self.curpos.x - self.size.x + 1;
+        }
+    }
+
+    // Re-queries the window size and re-clamps cursor/scroll state against
+    // it. Called when SIGWINCH indicates the terminal was resized.
+    fn refresh_size(&mut self) -> Result<(), GenericError> {
+        self.size = match get_window_size(self.tty_fd) {
+            Ok(size) => size,
+            Err(_) => get_window_size_via_cursor(&mut *self.istream, &mut *self.ostream)?,
+        };
+
+        self.curpos.y = self.curpos.y.min(self.max_curpos_y());
+        self.curpos.x = self.curpos.x.min(self.current_row_width());
+        self.clamp_scroll();
+
+        Ok(())
     }
 
     fn update(&mut self) -> Result<bool, GenericError> {
+        if WINDOW_RESIZED.swap(false, Ordering::SeqCst) {
+            self.refresh_size()?;
+        }
+
         self.print(HIDE_CURSOR);
         self.print(CURSOR_TO_START);
 
         for i in 0..self.size.y {
-            self.print(b"~");
-            self.print(CLEAR_LINE);
-
-            if i == self.size.y / 3 {
-                let welcome = b"Welcome to textedit";
-                let lmargin = (self.size.x - welcome.len()) / 2 - 1;
-                for _ in 0..lmargin {
-                    self.print(b" ");
+            let file_row = i + self.rowoff;
+
+            if file_row < self.rows.len() {
+                let line = clip_row(&self.rows[file_row], self.coloff, self.size.x);
+                self.print(line.as_bytes());
+            } else {
+                self.print(b"~");
+
+                // only show the welcome banner when no document is loaded
+                if self.rows.is_empty() && i == self.size.y / 3 {
+                    let welcome = b"Welcome to textedit";
+                    let lmargin = (self.size.x - welcome.len()) / 2 - 1;
+                    for _ in 0..lmargin {
+                        self.print(b" ");
+                    }
+                    self.print(welcome);
                 }
-                self.print(welcome);
             }
 
+            self.print(CLEAR_LINE);
+
             if i < self.size.y - 1 {
                 self.print(b"\r\n");
             }
         }
 
-        let move_cursor = format!("\x1b[{};{}H", self.curpos.y + 1, self.curpos.x + 1);
+        let move_cursor = format!(
+            "\x1b[{};{}H",
+            self.curpos.y - self.rowoff + 1,
+            self.curpos.x - self.coloff + 1
+        );
         self.print(move_cursor.as_bytes());
         self.print(SHOW_CURSOR);
         self.flush()?;
@@ -253,26 +620,20 @@ impl Editor<'_> {
     }
 }
 
-fn main() {
-    let mut istream = File::open("/dev/tty").unwrap();
+fn main() -> Result<(), GenericError> {
+    let mut istream = File::open("/dev/tty")?;
     let tty_fd = istream.as_raw_fd();
     let stdout = std::io::stdout();
     let mut ostream = stdout.lock();
-    let mut e = Editor::new(&mut istream, &mut ostream, tty_fd).unwrap();
+    let mut e = Editor::new(&mut istream, &mut ostream, tty_fd)?;
 
-    e.apply_term_settings().unwrap();
+    if let Some(path) = std::env::args().nth(1) {
+        e.open(&path)?;
+    }
 
-    loop {
-        match e.update() {
-            Ok(true) => break,
-            Ok(false) => (),
-            Err(err) => {
-                e.apply_prev_term_settings().unwrap();
-                eprintln!("{:?}", err);
-                std::process::exit(1);
-            }
-        }
+    while !e.update()? {
+        // keep rendering/handling input until the editor quits
     }
 
-    e.apply_prev_term_settings().unwrap();
+    Ok(())
 }